@@ -1,10 +1,13 @@
 use crate::clients::strava::StravaClient;
 use crate::config::Config;
+use crate::db::Store;
+use crate::tasks::{self, Command};
 use std::sync::Arc;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
 pub struct App {
     strava_client: Arc<StravaClient>,
+    store: Arc<Store>,
     scheduler: JobScheduler,
 }
 
@@ -13,44 +16,58 @@ impl App {
         let config =
             Config::from_env().map_err(|e| anyhow::anyhow!("Failed to load config: {}", e))?;
 
-        let strava_client = Arc::new(StravaClient::new(
-            config.strava_client_id,
-            config.strava_client_secret,
-            config.strava_refresh_token,
-        )?);
+        let store = Arc::new(Store::connect(&config.database_url).await?);
+
+        let strava_client = Arc::new(
+            StravaClient::new(
+                config.strava_client_id,
+                config.strava_client_secret,
+                config.strava_refresh_token,
+                store.clone(),
+            )
+            .await?,
+        );
 
         let scheduler = JobScheduler::new().await?;
 
         Ok(Self {
             strava_client,
+            store,
             scheduler,
         })
     }
 
+    /// Enqueues the one-time historical backfill if it hasn't run before, so
+    /// older indoor/virtual duplicates outside the regular sync window get
+    /// reconciled exactly once.
+    async fn enqueue_backfill_if_needed(&self) -> anyhow::Result<()> {
+        if self.store.is_backfill_done().await? {
+            return Ok(());
+        }
+
+        let id = tasks::enqueue(
+            &self.store,
+            Command::Backfill {
+                before: None,
+                after: None,
+            },
+        )
+        .await?;
+        println!("Enqueued one-time historical backfill task #{}", id);
+
+        Ok(())
+    }
+
     async fn setup_jobs(&self) -> anyhow::Result<()> {
-        let client = self.strava_client.clone();
+        let store = self.store.clone();
 
         self.scheduler
             .add(Job::new_async("0 */15 * * * *", move |_uuid, _l| {
-                let client = client.clone();
+                let store = store.clone();
                 Box::pin(async move {
-                    match client.get_all_activities(1, 50).await {
-                        Ok(_) => match client.hide_duplicate_indoor_rides().await {
-                            Ok(result) => {
-                                if !result.hidden.is_empty() {
-                                    println!(
-                                        "Hidden {} duplicate indoor bike activities",
-                                        result.hidden.len()
-                                    );
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to hide duplicates: {}", e);
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to fetch activities: {}", e);
-                        }
+                    match tasks::enqueue(&store, Command::SyncActivities).await {
+                        Ok(id) => println!("Enqueued sync task #{}", id),
+                        Err(e) => eprintln!("Failed to enqueue sync task: {}", e),
                     }
                 })
             })?)
@@ -61,8 +78,15 @@ impl App {
     }
 
     pub async fn run(self) -> anyhow::Result<()> {
+        self.enqueue_backfill_if_needed().await?;
         self.setup_jobs().await?;
         self.scheduler.start().await?;
+
+        tokio::spawn(tasks::run_worker(
+            self.store.clone(),
+            self.strava_client.clone(),
+        ));
+
         println!("Scheduler started. Press Ctrl+C to exit.");
         println!("Sync runs every 15 minutes at :00, :15, :30, :45");
 
@@ -0,0 +1,21 @@
+use async_trait::async_trait;
+
+use crate::clients::strava::{StravaActivitySummary, UpdateDetails};
+
+/// The subset of the Strava API that the duplicate-hiding logic depends on,
+/// extracted so it can be driven by an in-memory mock in tests instead of
+/// the real HTTP client.
+#[async_trait]
+pub trait StravaApi: Send + Sync {
+    async fn get_all_activities(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> anyhow::Result<Vec<StravaActivitySummary>>;
+
+    async fn update_activity(
+        &self,
+        activity_id: String,
+        update_details: UpdateDetails,
+    ) -> anyhow::Result<StravaActivitySummary>;
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+
+/// A single field-level complaint from Strava's error envelope, e.g.
+/// `{"resource": "Activity", "field": "hide_from_home", "code": "invalid"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StravaErrorDetail {
+    pub resource: String,
+    pub field: String,
+    pub code: String,
+}
+
+/// Strava's JSON error body, returned on non-2xx responses.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StravaErrorBody {
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub errors: Vec<StravaErrorDetail>,
+}
+
+impl fmt::Display for StravaErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        for detail in &self.errors {
+            write!(f, " ({}.{}: {})", detail.resource, detail.field, detail.code)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A typed Strava API error, carrying the HTTP status and the structured
+/// error body so callers can distinguish e.g. `invalid`/`not found`/
+/// `exceeded` field codes instead of matching on raw status codes.
+#[derive(Debug, thiserror::Error)]
+pub enum StravaApiError {
+    #[error("strava api error ({status}): {body}")]
+    Api {
+        status: StatusCode,
+        body: StravaErrorBody,
+    },
+    #[error("strava rate limit exceeded, all retries exhausted")]
+    RateLimitExceeded,
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+impl StravaApiError {
+    /// Builds a `StravaApiError` from a non-2xx response, parsing Strava's
+    /// error envelope where present and falling back to an empty body
+    /// otherwise (e.g. for 5xx responses with no JSON payload).
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let body = response
+            .json::<StravaErrorBody>()
+            .await
+            .unwrap_or_default();
+
+        Self::Api { status, body }
+    }
+
+    /// True if any field-level error carries the given `code` (e.g.
+    /// `"invalid"`, `"not found"`, `"exceeded"`).
+    pub fn has_code(&self, code: &str) -> bool {
+        match self {
+            Self::Api { body, .. } => body.errors.iter().any(|e| e.code == code),
+            _ => false,
+        }
+    }
+}
@@ -2,14 +2,22 @@ use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, Utc};
-use reqwest::Client;
+use async_trait::async_trait;
+use chrono::{DateTime, Timelike, Utc};
+use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::time::Duration;
 use url::Url;
 
+use crate::clients::api::StravaApi;
+use crate::clients::error::StravaApiError;
+use crate::db::{PersistedToken, Store};
+
 const VIRTUAL_RIDE_ACTIVITY_TYPE: &str = "VirtualRide";
 const BIKE_RIDE_ACTIVITY_TYPE: &str = "Ride";
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+const DEFAULT_RATE_LIMIT_WAIT: Duration = Duration::from_secs(15 * 60);
 
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
@@ -32,9 +40,10 @@ pub struct StravaClient {
     client_secret: String,
     token: Arc<Mutex<TokenState>>,
     processed_activities: Arc<Mutex<HashSet<u64>>>,
+    store: Arc<Store>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StravaActivitySummary {
     pub id: u64,
     pub name: String,
@@ -45,7 +54,7 @@ pub struct StravaActivitySummary {
     pub private: bool,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct UpdateDetails {
     hide_from_home: Option<bool>,
     name: Option<String>,
@@ -76,7 +85,35 @@ pub struct CleanupResult {
 }
 
 impl StravaClient {
-    pub fn new(id: String, secret: String, initial_refresh_token: String) -> anyhow::Result<Self> {
+    /// Builds a client, seeding `TokenState` and the processed-activity set
+    /// from the persisted store when present, falling back to
+    /// `initial_refresh_token` (e.g. `STRAVA_INITIAL_REFRESH_TOKEN`) only on
+    /// first run.
+    pub async fn new(
+        id: String,
+        secret: String,
+        initial_refresh_token: String,
+        store: Arc<Store>,
+    ) -> anyhow::Result<Self> {
+        let token = match store.load_token().await? {
+            Some(PersistedToken {
+                access_token,
+                refresh_token,
+                expires_at,
+            }) => TokenState {
+                access_token,
+                refresh_token,
+                expires_at,
+            },
+            None => TokenState {
+                access_token: String::new(),
+                refresh_token: initial_refresh_token,
+                expires_at: 0,
+            },
+        };
+
+        let processed_activities = store.load_processed_activities().await?;
+
         Ok(Self {
             http: Client::builder()
                 .timeout(std::time::Duration::from_secs(10))
@@ -84,12 +121,9 @@ impl StravaClient {
             base: Url::parse("https://www.strava.com/api/v3/")?,
             client_id: id,
             client_secret: secret,
-            token: Arc::new(Mutex::new(TokenState {
-                access_token: String::new(),
-                refresh_token: initial_refresh_token,
-                expires_at: 0,
-            })),
-            processed_activities: Arc::new(Mutex::new(HashSet::new())),
+            token: Arc::new(Mutex::new(token)),
+            processed_activities: Arc::new(Mutex::new(processed_activities)),
+            store,
         })
     }
 
@@ -109,24 +143,35 @@ impl StravaClient {
             token_state.refresh_token.clone()
         };
 
-        let response = Client::new()
-            .post("https://www.strava.com/oauth/token")
-            .json(&json!({
-                "client_id": self.client_id,
-                "client_secret": self.client_secret,
-                "refresh_token": refresh_token,
-                "grant_type": "refresh_token"
-            }))
-            .send()
+        let response = self
+            .send_with_rate_limit_retry(|| {
+                self.http.post("https://www.strava.com/oauth/token").json(&json!({
+                    "client_id": self.client_id,
+                    "client_secret": self.client_secret,
+                    "refresh_token": refresh_token,
+                    "grant_type": "refresh_token"
+                }))
+            })
             .await?
-            .error_for_status()?
             .json::<TokenResponse>()
             .await?;
 
-        let mut token_state = self.token.lock().unwrap();
-        token_state.access_token = response.access_token;
-        token_state.refresh_token = response.refresh_token;
-        token_state.expires_at = response.expires_at;
+        let persisted = PersistedToken {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            expires_at: response.expires_at,
+        };
+
+        {
+            let mut token_state = self.token.lock().unwrap();
+            token_state.access_token = persisted.access_token.clone();
+            token_state.refresh_token = persisted.refresh_token.clone();
+            token_state.expires_at = persisted.expires_at;
+        }
+
+        // Strava rotates the refresh token on every exchange, so persist it
+        // immediately or a restart falls back to the stale one in the env.
+        self.store.save_token(&persisted).await?;
 
         Ok(())
     }
@@ -140,31 +185,116 @@ impl StravaClient {
         Ok(token_state.access_token.clone())
     }
 
+    /// Sends the request built by `build_request`, transparently retrying on
+    /// HTTP 429 by sleeping until Strava's rate-limit window resets (honoring
+    /// `Retry-After` when present, otherwise the `X-RateLimit-*` headers).
+    /// Returns the first non-429 response, success or failure, as a typed
+    /// `StravaApiError` so callers can distinguish failure modes.
+    async fn send_with_rate_limit_retry(
+        &self,
+        build_request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<Response, StravaApiError> {
+        for attempt in 0..=MAX_RATE_LIMIT_RETRIES {
+            let response = build_request().send().await?;
+
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return if response.status().is_success() {
+                    Ok(response)
+                } else {
+                    Err(StravaApiError::from_response(response).await)
+                };
+            }
+
+            if attempt == MAX_RATE_LIMIT_RETRIES {
+                return Err(StravaApiError::RateLimitExceeded);
+            }
+
+            let wait = rate_limit_wait(&response);
+            eprintln!(
+                "Strava rate limit hit (attempt {}/{}), sleeping {:?} before retrying",
+                attempt + 1,
+                MAX_RATE_LIMIT_RETRIES,
+                wait
+            );
+            tokio::time::sleep(wait).await;
+        }
+
+        unreachable!("loop always returns before exhausting its range")
+    }
+
     pub async fn get_all_activities(
         &self,
         page: u32,
         per_page: u32,
+    ) -> anyhow::Result<Vec<StravaActivitySummary>> {
+        self.get_activities_page(page, per_page, None, None).await
+    }
+
+    /// Fetches a single page of `athlete/activities`, optionally windowed by
+    /// `before`/`after` (epoch seconds) so a backfill can be chunked into
+    /// smaller time ranges instead of walking the entire history in one go.
+    async fn get_activities_page(
+        &self,
+        page: u32,
+        per_page: u32,
+        before: Option<i64>,
+        after: Option<i64>,
     ) -> anyhow::Result<Vec<StravaActivitySummary>> {
         let token = self.get_valid_token().await?;
         let url = self.base.join("athlete/activities")?;
 
+        let mut query = vec![
+            ("page".to_string(), page.to_string()),
+            ("per_page".to_string(), per_page.to_string()),
+        ];
+        if let Some(before) = before {
+            query.push(("before".to_string(), before.to_string()));
+        }
+        if let Some(after) = after {
+            query.push(("after".to_string(), after.to_string()));
+        }
+
         let activities = self
-            .http
-            .get(url)
-            .bearer_auth(token)
-            .query(&[
-                ("page", page.to_string()),
-                ("per_page", per_page.to_string()),
-            ])
-            .send()
+            .send_with_rate_limit_retry(|| {
+                self.http.get(url.clone()).bearer_auth(&token).query(&query)
+            })
             .await?
-            .error_for_status()?
             .json::<Vec<StravaActivitySummary>>()
             .await?;
 
         Ok(activities)
     }
 
+    /// Walks `athlete/activities` with increasing `page`, accumulating every
+    /// `StravaActivitySummary` in the (optionally `before`/`after`-windowed)
+    /// range until a short or empty page signals there's nothing left.
+    pub async fn get_all_activities_paginated(
+        &self,
+        before: Option<i64>,
+        after: Option<i64>,
+    ) -> anyhow::Result<Vec<StravaActivitySummary>> {
+        const PER_PAGE: u32 = 200;
+
+        let mut all_activities = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let batch = self
+                .get_activities_page(page, PER_PAGE, before, after)
+                .await?;
+            let batch_len = batch.len() as u32;
+
+            all_activities.extend(batch);
+
+            if batch_len < PER_PAGE {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(all_activities)
+    }
+
     pub async fn update_activity(
         &self,
         activity_id: String,
@@ -173,100 +303,210 @@ impl StravaClient {
         let token = self.get_valid_token().await?;
         let url = self.base.join(&format!("activities/{}", activity_id))?;
 
-        let result = self
-            .http
-            .put(url)
-            .bearer_auth(token)
-            .json(&update_details)
-            .send()
-            .await?;
-
-        if !result.status().is_success() {
-            eprintln!(
-                "Error updating activity {}: status={}, statusText={}",
-                activity_id,
-                result.status().as_u16(),
-                result.status().canonical_reason().unwrap_or("Unknown")
-            );
-        }
-
-        let activity = result
-            .error_for_status()?
+        let activity = self
+            .send_with_rate_limit_retry(|| {
+                self.http
+                    .put(url.clone())
+                    .bearer_auth(&token)
+                    .json(&update_details)
+            })
+            .await?
             .json::<StravaActivitySummary>()
             .await?;
 
         Ok(activity)
     }
 
-    pub async fn hide_duplicate_indoor_rides(&self) -> anyhow::Result<CleanupResult> {
-        let all_activities = self.get_all_activities(1, 200).await?;
+    /// Hides duplicates over a single page, so a failed
+    /// `Command::HideDuplicatesPage` task can be retried in isolation
+    /// without re-scanning every other page.
+    pub async fn hide_duplicates_page(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> anyhow::Result<CleanupResult> {
+        let processed_set = {
+            let processed = self.processed_activities.lock().unwrap();
+            processed.clone()
+        };
+
+        let result = find_and_hide_duplicates(self, page, per_page, &processed_set).await?;
+        self.persist_hidden(&result.hidden).await?;
 
+        Ok(result)
+    }
+
+    /// One-time historical backfill: walks the athlete's entire activity
+    /// history (optionally windowed by `before`/`after` epoch seconds so it
+    /// can be chunked) and reconciles indoor/virtual ride duplicates that
+    /// predate the bot's regular 200-item sync window.
+    pub async fn backfill(
+        &self,
+        before: Option<i64>,
+        after: Option<i64>,
+    ) -> anyhow::Result<CleanupResult> {
         let processed_set = {
             let processed = self.processed_activities.lock().unwrap();
             processed.clone()
         };
 
-        let public_indoor_bike_activities: Vec<_> = all_activities
-            .iter()
-            .filter(|activity| {
-                is_indoor_bike_activity(activity)
-                    && !activity.private
-                    && !processed_set.contains(&activity.id)
-            })
-            .cloned()
-            .collect();
-
-        let all_virtual_ride_activities: Vec<_> = all_activities
-            .iter()
-            .filter(|activity| activity.activity_type == VIRTUAL_RIDE_ACTIVITY_TYPE)
-            .cloned()
-            .collect();
-
-        let mut hidden_activity_ids = Vec::new();
-        let mut matched_activity_pairs = Vec::new();
-
-        for indoor_bike_activity in public_indoor_bike_activities {
-            if let Some(corresponding_virtual_ride) =
-                all_virtual_ride_activities.iter().find(|virtual_ride| {
-                    are_activities_within_one_hour(&indoor_bike_activity, virtual_ride)
-                })
+        let all_activities = self.get_all_activities_paginated(before, after).await?;
+        let result = hide_duplicates_in(self, &all_activities, &processed_set).await?;
+        self.persist_hidden(&result.hidden).await?;
+
+        Ok(result)
+    }
+
+    async fn persist_hidden(&self, hidden: &[u64]) -> anyhow::Result<()> {
+        for &activity_id in hidden {
             {
-                matched_activity_pairs.push(ActivityMatch {
-                    indoor_activity: ActivityInfo {
-                        id: indoor_bike_activity.id,
-                        name: indoor_bike_activity.name.clone(),
-                        start_date: indoor_bike_activity.start_date.clone(),
-                    },
-                    virtual_ride: ActivityInfo {
-                        id: corresponding_virtual_ride.id,
-                        name: corresponding_virtual_ride.name.clone(),
-                        start_date: corresponding_virtual_ride.start_date.clone(),
-                    },
-                });
+                let mut processed = self.processed_activities.lock().unwrap();
+                processed.insert(activity_id);
+            }
+            self.store.mark_activity_processed(activity_id).await?;
+        }
+
+        Ok(())
+    }
+}
 
-                self.update_activity(
+#[async_trait]
+impl StravaApi for StravaClient {
+    async fn get_all_activities(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> anyhow::Result<Vec<StravaActivitySummary>> {
+        StravaClient::get_all_activities(self, page, per_page).await
+    }
+
+    async fn update_activity(
+        &self,
+        activity_id: String,
+        update_details: UpdateDetails,
+    ) -> anyhow::Result<StravaActivitySummary> {
+        StravaClient::update_activity(self, activity_id, update_details).await
+    }
+}
+
+/// The core matching/hiding logic over a single page, driven by `api` rather
+/// than the real HTTP client so it can be exercised offline with a mock in
+/// tests. `already_processed` activity ids are skipped so a restart (or a
+/// second pass over the same page) doesn't re-hide what's already been
+/// handled.
+pub async fn find_and_hide_duplicates(
+    api: &dyn StravaApi,
+    page: u32,
+    per_page: u32,
+    already_processed: &HashSet<u64>,
+) -> anyhow::Result<CleanupResult> {
+    let all_activities = api.get_all_activities(page, per_page).await?;
+    hide_duplicates_in(api, &all_activities, already_processed).await
+}
+
+/// Same matching/hiding logic as [`find_and_hide_duplicates`], but over an
+/// already-fetched set of activities (e.g. the full paginated history from
+/// [`StravaClient::backfill`]) rather than fetching a single page itself.
+async fn hide_duplicates_in(
+    api: &dyn StravaApi,
+    all_activities: &[StravaActivitySummary],
+    already_processed: &HashSet<u64>,
+) -> anyhow::Result<CleanupResult> {
+    let public_indoor_bike_activities: Vec<_> = all_activities
+        .iter()
+        .filter(|activity| {
+            is_indoor_bike_activity(activity)
+                && !activity.private
+                && !already_processed.contains(&activity.id)
+        })
+        .cloned()
+        .collect();
+
+    let all_virtual_ride_activities: Vec<_> = all_activities
+        .iter()
+        .filter(|activity| activity.activity_type == VIRTUAL_RIDE_ACTIVITY_TYPE)
+        .cloned()
+        .collect();
+
+    let mut hidden_activity_ids = Vec::new();
+    let mut matched_activity_pairs = Vec::new();
+
+    for indoor_bike_activity in public_indoor_bike_activities {
+        if let Some(corresponding_virtual_ride) =
+            all_virtual_ride_activities.iter().find(|virtual_ride| {
+                are_activities_within_one_hour(&indoor_bike_activity, virtual_ride)
+            })
+        {
+            matched_activity_pairs.push(ActivityMatch {
+                indoor_activity: ActivityInfo {
+                    id: indoor_bike_activity.id,
+                    name: indoor_bike_activity.name.clone(),
+                    start_date: indoor_bike_activity.start_date.clone(),
+                },
+                virtual_ride: ActivityInfo {
+                    id: corresponding_virtual_ride.id,
+                    name: corresponding_virtual_ride.name.clone(),
+                    start_date: corresponding_virtual_ride.start_date.clone(),
+                },
+            });
+
+            match api
+                .update_activity(
                     indoor_bike_activity.id.to_string(),
                     UpdateDetails {
                         hide_from_home: Some(true),
                         ..Default::default()
                     },
                 )
-                .await?;
-
-                {
-                    let mut processed = self.processed_activities.lock().unwrap();
-                    processed.insert(indoor_bike_activity.id);
+                .await
+            {
+                Ok(_) => hidden_activity_ids.push(indoor_bike_activity.id),
+                // Strava returns a "not found" field error when the activity
+                // was deleted between listing and hiding it; skip it and
+                // keep processing the rest of the batch instead of aborting.
+                Err(e) if matches!(e.downcast_ref::<StravaApiError>(), Some(e) if e.has_code("not found")) => {
+                    continue;
                 }
-
-                hidden_activity_ids.push(indoor_bike_activity.id);
+                Err(e) => return Err(e),
             }
         }
+    }
 
-        Ok(CleanupResult {
-            hidden: hidden_activity_ids,
-            matches: matched_activity_pairs,
-        })
+    Ok(CleanupResult {
+        hidden: hidden_activity_ids,
+        matches: matched_activity_pairs,
+    })
+}
+
+/// How long to sleep before retrying a 429. Prefers `Retry-After` when
+/// Strava sends it; otherwise, if the `X-RateLimit-*` headers show we're
+/// rate limited, waits out the remainder of Strava's 15-minute window
+/// (which resets on the clock quarter-hour); otherwise falls back to a
+/// conservative default.
+fn rate_limit_wait(response: &Response) -> Duration {
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    if let Some(seconds) = retry_after {
+        return Duration::from_secs(seconds);
+    }
+
+    if response.headers().contains_key("X-RateLimit-Usage") {
+        return time_until_next_quarter_hour();
     }
+
+    DEFAULT_RATE_LIMIT_WAIT
+}
+
+fn time_until_next_quarter_hour() -> Duration {
+    let now = Utc::now();
+    let minutes_into_window = now.minute() % 15;
+    let seconds_remaining = (15 - minutes_into_window) * 60 - now.second();
+
+    Duration::from_secs(seconds_remaining as u64 + 1)
 }
 
 pub fn is_indoor_bike_activity(activity: &StravaActivitySummary) -> bool {
@@ -300,6 +540,48 @@ pub fn are_activities_within_one_hour(
 mod tests {
     use super::*;
 
+    /// In-memory `StravaApi` fixture: serves a fixed set of activities and
+    /// records which ids get updated, so `find_and_hide_duplicates` can be
+    /// exercised without a real HTTP client.
+    struct MockStravaApi {
+        activities: Vec<StravaActivitySummary>,
+        updated: Mutex<Vec<String>>,
+    }
+
+    impl MockStravaApi {
+        fn new(activities: Vec<StravaActivitySummary>) -> Self {
+            Self {
+                activities,
+                updated: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl StravaApi for MockStravaApi {
+        async fn get_all_activities(
+            &self,
+            _page: u32,
+            _per_page: u32,
+        ) -> anyhow::Result<Vec<StravaActivitySummary>> {
+            Ok(self.activities.clone())
+        }
+
+        async fn update_activity(
+            &self,
+            activity_id: String,
+            _update_details: UpdateDetails,
+        ) -> anyhow::Result<StravaActivitySummary> {
+            self.updated.lock().unwrap().push(activity_id.clone());
+
+            self.activities
+                .iter()
+                .find(|activity| activity.id.to_string() == activity_id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("unknown activity {}", activity_id))
+        }
+    }
+
     fn create_activity(
         id: u64,
         name: &str,
@@ -412,4 +694,108 @@ mod tests {
         assert_eq!(cleanup_result.hidden.len(), deserialized.hidden.len());
         assert_eq!(cleanup_result.matches.len(), deserialized.matches.len());
     }
+
+    #[tokio::test]
+    async fn test_find_and_hide_duplicates_hides_matching_indoor_ride() {
+        let activities = vec![
+            create_activity(1, "Indoor Bike", "Ride", "2025-01-01T10:00:00Z", 0.0, false),
+            create_activity(
+                2,
+                "Zwift Ride",
+                "VirtualRide",
+                "2025-01-01T10:10:00Z",
+                25000.0,
+                false,
+            ),
+        ];
+        let api = MockStravaApi::new(activities);
+
+        let result = find_and_hide_duplicates(&api, 1, 200, &HashSet::new())
+            .await
+            .unwrap();
+
+        assert_eq!(result.hidden, vec![1]);
+        assert_eq!(result.matches.len(), 1);
+        assert_eq!(result.matches[0].virtual_ride.id, 2);
+        assert_eq!(*api.updated.lock().unwrap(), vec!["1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_find_and_hide_duplicates_skips_already_processed() {
+        let activities = vec![
+            create_activity(1, "Indoor Bike", "Ride", "2025-01-01T10:00:00Z", 0.0, false),
+            create_activity(
+                2,
+                "Zwift Ride",
+                "VirtualRide",
+                "2025-01-01T10:10:00Z",
+                25000.0,
+                false,
+            ),
+        ];
+        let api = MockStravaApi::new(activities);
+        let already_processed = HashSet::from([1]);
+
+        let result = find_and_hide_duplicates(&api, 1, 200, &already_processed)
+            .await
+            .unwrap();
+
+        assert!(result.hidden.is_empty());
+        assert!(result.matches.is_empty());
+        assert!(api.updated.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_and_hide_duplicates_matches_first_virtual_ride_within_the_hour() {
+        let activities = vec![
+            create_activity(1, "Indoor Bike", "Ride", "2025-01-01T10:00:00Z", 0.0, false),
+            create_activity(
+                2,
+                "Zwift Ride A",
+                "VirtualRide",
+                "2025-01-01T10:05:00Z",
+                25000.0,
+                false,
+            ),
+            create_activity(
+                3,
+                "Zwift Ride B",
+                "VirtualRide",
+                "2025-01-01T10:45:00Z",
+                18000.0,
+                false,
+            ),
+        ];
+        let api = MockStravaApi::new(activities);
+
+        let result = find_and_hide_duplicates(&api, 1, 200, &HashSet::new())
+            .await
+            .unwrap();
+
+        assert_eq!(result.hidden, vec![1]);
+        assert_eq!(result.matches[0].virtual_ride.id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_find_and_hide_duplicates_ignores_private_indoor_rides() {
+        let activities = vec![
+            create_activity(1, "Indoor Bike", "Ride", "2025-01-01T10:00:00Z", 0.0, true),
+            create_activity(
+                2,
+                "Zwift Ride",
+                "VirtualRide",
+                "2025-01-01T10:10:00Z",
+                25000.0,
+                false,
+            ),
+        ];
+        let api = MockStravaApi::new(activities);
+
+        let result = find_and_hide_duplicates(&api, 1, 200, &HashSet::new())
+            .await
+            .unwrap();
+
+        assert!(result.hidden.is_empty());
+        assert!(api.updated.lock().unwrap().is_empty());
+    }
 }
@@ -5,6 +5,7 @@ pub struct Config {
     pub strava_client_id: String,
     pub strava_client_secret: String,
     pub strava_refresh_token: String,
+    pub database_url: String,
 }
 
 impl Config {
@@ -13,6 +14,7 @@ impl Config {
             strava_client_id: env::var("STRAVA_CLIENT_ID")?,
             strava_client_secret: env::var("STRAVA_CLIENT_SECRET")?,
             strava_refresh_token: env::var("STRAVA_INITIAL_REFRESH_TOKEN")?,
+            database_url: env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://strava-bot.db".to_string()),
         })
     }
 }
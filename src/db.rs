@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+
+/// A task dequeued for dispatch, still carrying its JSON-serialized
+/// `Command` for the caller to deserialize and run.
+pub struct PersistedTask {
+    pub id: i64,
+    pub command_json: String,
+    pub attempts: i64,
+}
+
+/// Snapshot of the OAuth token state as persisted across restarts.
+#[derive(Debug, Clone)]
+pub struct PersistedToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: u64,
+}
+
+/// SQLite-backed store for OAuth token state and already-processed activities,
+/// so a restart doesn't throw away a freshly rotated refresh token or
+/// re-evaluate activities the bot has already hidden.
+pub struct Store {
+    pool: SqlitePool,
+}
+
+impl Store {
+    pub async fn connect(database_url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn load_token(&self) -> anyhow::Result<Option<PersistedToken>> {
+        let row = sqlx::query_as!(
+            PersistedTokenRow,
+            "SELECT access_token, refresh_token, expires_at FROM token_state WHERE id = 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(PersistedToken::from))
+    }
+
+    pub async fn save_token(&self, token: &PersistedToken) -> anyhow::Result<()> {
+        let expires_at = token.expires_at as i64;
+
+        sqlx::query!(
+            "INSERT INTO token_state (id, access_token, refresh_token, expires_at)
+             VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET
+                access_token = excluded.access_token,
+                refresh_token = excluded.refresh_token,
+                expires_at = excluded.expires_at",
+            token.access_token,
+            token.refresh_token,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn load_processed_activities(&self) -> anyhow::Result<HashSet<u64>> {
+        let rows = sqlx::query!("SELECT activity_id FROM processed_activities")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| row.activity_id as u64).collect())
+    }
+
+    pub async fn mark_activity_processed(&self, activity_id: u64) -> anyhow::Result<()> {
+        let activity_id = activity_id as i64;
+
+        sqlx::query!(
+            "INSERT OR IGNORE INTO processed_activities (activity_id) VALUES (?1)",
+            activity_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Enqueues a JSON-serialized `Command` as a pending task.
+    pub async fn enqueue_task(&self, command_json: &str) -> anyhow::Result<i64> {
+        let now = current_epoch_secs();
+
+        let result = sqlx::query!(
+            "INSERT INTO tasks (command, status, created_at, updated_at)
+             VALUES (?1, 'pending', ?2, ?2)",
+            command_json,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Claims the oldest pending task in a single statement, so a second
+    /// worker racing to dequeue at the same time can't claim the same row:
+    /// the `UPDATE ... WHERE id = (SELECT ...)` is one atomic write under
+    /// SQLite's single-writer model, unlike a separate select-then-update.
+    pub async fn dequeue_next_task(&self) -> anyhow::Result<Option<PersistedTask>> {
+        let now = current_epoch_secs();
+
+        let row = sqlx::query!(
+            "UPDATE tasks
+             SET status = 'running', attempts = attempts + 1, updated_at = ?1
+             WHERE id = (SELECT id FROM tasks WHERE status = 'pending' ORDER BY id ASC LIMIT 1)
+             RETURNING id, command, attempts",
+            now,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| PersistedTask {
+            id: row.id,
+            command_json: row.command,
+            attempts: row.attempts,
+        }))
+    }
+
+    /// Puts a failed task back on the queue so it's picked up again, instead
+    /// of being stuck in `running` or marked terminally `failed` after a
+    /// single transient error.
+    pub async fn requeue_task(&self, id: i64) -> anyhow::Result<()> {
+        let now = current_epoch_secs();
+
+        sqlx::query!(
+            "UPDATE tasks SET status = 'pending', updated_at = ?2 WHERE id = ?1",
+            id,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_task_done(&self, id: i64, result_json: &str) -> anyhow::Result<()> {
+        let now = current_epoch_secs();
+
+        sqlx::query!(
+            "UPDATE tasks SET status = 'done', result = ?2, updated_at = ?3 WHERE id = ?1",
+            id,
+            result_json,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_task_failed(&self, id: i64, error: &str) -> anyhow::Result<()> {
+        let now = current_epoch_secs();
+
+        sqlx::query!(
+            "UPDATE tasks SET status = 'failed', error = ?2, updated_at = ?3 WHERE id = ?1",
+            id,
+            error,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the one-time historical backfill has already completed, so
+    /// it isn't re-run (and the whole activity history re-scanned) on every
+    /// restart.
+    pub async fn is_backfill_done(&self) -> anyhow::Result<bool> {
+        let row = sqlx::query!("SELECT completed_at FROM backfill_state WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    pub async fn mark_backfill_done(&self) -> anyhow::Result<()> {
+        let now = current_epoch_secs();
+
+        sqlx::query!(
+            "INSERT INTO backfill_state (id, completed_at) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET completed_at = excluded.completed_at",
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn current_epoch_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+struct PersistedTokenRow {
+    access_token: String,
+    refresh_token: String,
+    expires_at: i64,
+}
+
+impl From<PersistedTokenRow> for PersistedToken {
+    fn from(row: PersistedTokenRow) -> Self {
+        Self {
+            access_token: row.access_token,
+            refresh_token: row.refresh_token,
+            expires_at: row.expires_at as u64,
+        }
+    }
+}
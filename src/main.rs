@@ -1,6 +1,8 @@
 mod app;
 mod clients;
 mod config;
+mod db;
+mod tasks;
 
 use app::App;
 
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::strava::{StravaClient, UpdateDetails};
+use crate::db::Store;
+
+/// One unit of work the task queue can dispatch. Persisted as JSON so the
+/// queue survives restarts, gives an audit trail of what the bot changed,
+/// and lets a single failed task be retried in isolation without
+/// re-scanning everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    SyncActivities,
+    HideDuplicatesPage { page: u32, per_page: u32 },
+    UpdateActivity { id: u64, details: UpdateDetails },
+    Backfill {
+        before: Option<i64>,
+        after: Option<i64>,
+    },
+}
+
+/// How long the worker sleeps after finding the queue empty before polling
+/// again.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many times a task is retried before it's given up on and marked
+/// terminally `failed`.
+const MAX_ATTEMPTS: i64 = 3;
+
+/// Enqueues `command` as a pending task and returns its id.
+pub async fn enqueue(store: &Store, command: Command) -> anyhow::Result<i64> {
+    let command_json = serde_json::to_string(&command)?;
+    store.enqueue_task(&command_json).await
+}
+
+/// Dequeues and dispatches a single task, recording its outcome. Returns
+/// `false` when the queue was empty so the caller knows to back off. A
+/// failed task is put back on the queue to retry until `MAX_ATTEMPTS`, then
+/// marked terminally `failed`.
+pub async fn run_once(store: &Store, client: &StravaClient) -> anyhow::Result<bool> {
+    let Some(task) = store.dequeue_next_task().await? else {
+        return Ok(false);
+    };
+
+    let command: Command = serde_json::from_str(&task.command_json)?;
+
+    match dispatch(store, client, command).await {
+        Ok(result) => store.mark_task_done(task.id, &result).await?,
+        Err(e) if task.attempts < MAX_ATTEMPTS => {
+            eprintln!(
+                "Task #{} failed (attempt {}/{}), requeuing: {}",
+                task.id, task.attempts, MAX_ATTEMPTS, e
+            );
+            store.requeue_task(task.id).await?
+        }
+        Err(e) => store.mark_task_failed(task.id, &e.to_string()).await?,
+    }
+
+    Ok(true)
+}
+
+async fn dispatch(store: &Store, client: &StravaClient, command: Command) -> anyhow::Result<String> {
+    match command {
+        Command::SyncActivities => {
+            enqueue(
+                store,
+                Command::HideDuplicatesPage {
+                    page: 1,
+                    per_page: 200,
+                },
+            )
+            .await?;
+
+            Ok("synced".to_string())
+        }
+        Command::HideDuplicatesPage { page, per_page } => {
+            let result = client.hide_duplicates_page(page, per_page).await?;
+            Ok(serde_json::to_string(&result)?)
+        }
+        Command::UpdateActivity { id, details } => {
+            let activity = client.update_activity(id.to_string(), details).await?;
+            Ok(serde_json::to_string(&activity)?)
+        }
+        Command::Backfill { before, after } => {
+            let result = client.backfill(before, after).await?;
+            store.mark_backfill_done().await?;
+            Ok(serde_json::to_string(&result)?)
+        }
+    }
+}
+
+/// Polls the queue forever, dispatching one task at a time and sleeping
+/// briefly whenever it's empty.
+pub async fn run_worker(store: Arc<Store>, client: Arc<StravaClient>) {
+    loop {
+        match run_once(&store, &client).await {
+            Ok(true) => continue,
+            Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                eprintln!("Task worker error: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}